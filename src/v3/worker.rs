@@ -1,23 +1,217 @@
 //! Worker pool for handling events from the X server and user actions
 use crate::v3::{
     bindings::{KeyBindings, KeyCode, MouseBindings, MouseEvent},
-    error::ErrorHandler,
+    error::{ErrorHandler, XError},
     handle::WmHandle,
 };
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use std::{fmt, thread};
-use tracing::trace;
+use crossbeam_deque::{Injector, Stealer, Worker as Deque};
+use std::{
+    any::Any,
+    collections::HashMap,
+    ffi::OsString,
+    fmt,
+    io::{BufRead, BufReader, Read},
+    iter,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{error, trace};
+
+/// How often the reaper thread polls for workers that have died unexpectedly.
+const REAPER_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long an idle worker sleeps between failed attempts to find a task
+/// before trying again, so an idle pool doesn't busy-spin.
+const IDLE_BACKOFF: Duration = Duration::from_millis(1);
+
+/// The worker that ordered X events (key presses, mouse events) are always
+/// pinned to, so that they are never reordered relative to one another. This
+/// worker never batch-steals from its own injector (see [`find_task`]), so
+/// the events it's the only consumer of come off in the order they were
+/// pushed.
+const DESIGNATED_WORKER: usize = 0;
 
+/// Identifies an in-flight job submitted to a [`Pool`].
+///
+/// Submitting a job under an id that is already in flight cancels the
+/// previous job with that id (e.g. cancel the previous "resize preview" job
+/// when a new one starts).
+pub type JobId = String;
+
+/// A cooperative cancellation flag handed to a job closure so it can notice
+/// that it has been superseded and abort early.
+#[derive(Debug, Clone)]
+pub struct JobToken(Arc<AtomicBool>);
+
+impl JobToken {
+    /// Whether the job holding this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// A handle to a job submitted via [`Pool::exec`] or [`Pool::spawn_process`].
+///
+/// Calling [`JobHandle::cancel`] explicitly always marks the job's
+/// [`JobToken`] as cancelled. Simply dropping the handle does *not*: the
+/// overwhelmingly common call pattern for e.g. launcher bindings is to
+/// ignore the return value, as in `pool.spawn_process("term", "alacritty",
+/// ..);`, and cancelling on drop there would kill the child before it even
+/// finishes starting. [`Pool`] keeps its own internal handle sharing the
+/// same underlying flag to implement id-collision eviction and
+/// cancel-on-shutdown; only that one cancels on drop.
 #[derive(Debug)]
+pub struct JobHandle {
+    id: JobId,
+    cancelled: Arc<AtomicBool>,
+    cancel_on_drop: bool,
+}
+
+impl JobHandle {
+    /// The id this job was submitted under.
+    pub fn id(&self) -> &JobId {
+        &self.id
+    }
+
+    /// Cancel the job associated with this handle.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        if self.cancel_on_drop {
+            self.cancel();
+        }
+    }
+}
+
+/// A progress event emitted by a worker as it dispatches a [`Message`],
+/// observable via [`Pool::events`] so the main event loop (or a status-bar
+/// integration) can watch action progress, timing and failures without
+/// polling.
+#[derive(Debug, Clone)]
+pub enum WorkerEvent {
+    /// A dispatch under the given id has started running.
+    Started(JobId),
+    /// A free-form progress message logged by a dispatch.
+    Log(String),
+    /// A dispatch under the given id completed successfully, having taken
+    /// the given amount of time.
+    Finished(JobId, Duration),
+    /// A dispatch failed, either by returning an error or by panicking.
+    Failed(XError),
+    /// A spawned child process was killed after exceeding its timeout.
+    TimedOut(JobId),
+    /// A spawned child process exited, carrying its exit status and how
+    /// long it ran for.
+    ProcessExited(JobId, ExitStatus, Duration),
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "worker panicked with a non-string payload".to_string()
+    }
+}
+
 enum Message {
     Key(KeyCode),
     Mouse(MouseEvent),
+    Job(
+        JobId,
+        Box<dyn FnOnce(WmHandle) -> Result<(), XError> + Send + 'static>,
+        JobToken,
+    ),
     ShutDown,
 }
 
+impl Message {
+    /// A label identifying this dispatch on the event bus: the job's own id
+    /// for [`Message::Job`], or a synthesised one for key/mouse bindings so
+    /// that binding dispatches can be timed and reported the same way.
+    fn label(&self) -> JobId {
+        match self {
+            Self::Key(k) => format!("key:{k:?}"),
+            Self::Mouse(e) => format!("mouse:{:?}", e.kind),
+            Self::Job(id, ..) => id.clone(),
+            Self::ShutDown => "shutdown".to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(k) => f.debug_tuple("Key").field(k).finish(),
+            Self::Mouse(e) => f.debug_tuple("Mouse").field(e).finish(),
+            Self::Job(id, _, token) => f
+                .debug_tuple("Job")
+                .field(id)
+                .field(&"<fn>")
+                .field(token)
+                .finish(),
+            Self::ShutDown => write!(f, "ShutDown"),
+        }
+    }
+}
+
+/// Pop the next [`Message`] a worker should run.
+///
+/// For most workers this is a throughput-oriented path: its own LIFO deque
+/// first, then a batch stolen from its own injector, and finally a single
+/// task stolen from a sibling worker's deque when this worker is otherwise
+/// idle (the jobsteal/rayon approach).
+///
+/// The designated worker (`ordered: true`) skips batching entirely and pops
+/// a single message at a time directly from its own injector instead:
+/// `steal_batch_and_pop` can move 2+ pending messages into `local` in FIFO
+/// order, but `local` is a LIFO deque, so they'd pop back out newest-first —
+/// reordering ordered key/mouse events relative to one another, exactly what
+/// pinning them to a single worker exists to prevent.
+fn find_task(
+    local: &Deque<Message>,
+    injector: &Injector<Message>,
+    stealers: &[Stealer<Message>],
+    ordered: bool,
+) -> Option<Message> {
+    if ordered {
+        return iter::repeat_with(|| {
+            injector
+                .steal()
+                .or_else(|| stealers.iter().map(Stealer::steal).collect())
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success());
+    }
+
+    local.pop().or_else(|| {
+        iter::repeat_with(|| {
+            injector
+                .steal_batch_and_pop(local)
+                .or_else(|| stealers.iter().map(Stealer::steal).collect())
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success())
+    })
+}
+
 struct Worker {
     id: usize,
     handle: thread::JoinHandle<()>,
+    injector: Arc<Injector<Message>>,
 }
 
 impl fmt::Debug for Worker {
@@ -27,50 +221,167 @@ impl fmt::Debug for Worker {
 }
 
 impl Worker {
+    /// Spawn a worker with its own LIFO deque and dedicated injector, able
+    /// to steal from `stealers` (the other workers' deques) when idle.
     fn new(
         id: usize,
-        rx: Receiver<Message>,
-        h: WmHandle,
-        ks: KeyBindings,
-        ms: MouseBindings,
-        error_handler: ErrorHandler,
+        local: Deque<Message>,
+        injector: Arc<Injector<Message>>,
+        stealers: Vec<Stealer<Message>>,
+        shared: &SharedState,
+        shutting_down: Arc<AtomicBool>,
     ) -> Self {
-        let handle = thread::spawn(move || {
-            while let Ok(m) = rx.recv() {
-                match m {
-                    Message::Key(k) => {
-                        if let Some(action) = ks.get_mut(&k) {
-                            if let Err(e) = action(h.clone()) {
-                                error_handler(e);
-                            }
-                        }
-                    }
+        let h = shared.h.clone();
+        let ks = shared.ks.clone();
+        let ms = shared.ms.clone();
+        let error_handler = shared.error_handler;
+        let events_tx = shared.events_tx.clone();
+        let jobs = Arc::clone(&shared.jobs);
+        let thread_injector = Arc::clone(&injector);
 
-                    Message::Mouse(e) => {
-                        if let Some(action) = ms.get_mut(&(e.kind, e.state.clone())) {
-                            if let Err(e) = action(h.clone(), &e) {
-                                error_handler(e);
-                            }
-                        }
-                    }
+        let ordered = id == DESIGNATED_WORKER;
 
-                    Message::ShutDown => {
-                        trace!(id, "Shutting down");
+        let handle = thread::spawn(move || loop {
+            let m = match find_task(&local, &thread_injector, &stealers, ordered) {
+                Some(m) => m,
+                None => {
+                    if shutting_down.load(Ordering::Acquire) {
                         return;
                     }
+                    thread::sleep(IDLE_BACKOFF);
+                    continue;
+                }
+            };
+
+            if let Message::ShutDown = m {
+                trace!(id, "Shutting down");
+                return;
+            }
+
+            let label = m.label();
+            let _ = events_tx.send(WorkerEvent::Started(label.clone()));
+            let started = Instant::now();
+
+            // A `Message::Job`'s id and cancellation flag, kept so the
+            // `jobs` bookkeeping entry `Pool::exec` inserted can be pruned
+            // once this dispatch finishes; `m` is consumed by the match
+            // below so this has to be captured up front.
+            let job_entry = match &m {
+                Message::Job(id, _, token) => Some((id.clone(), Arc::clone(&token.0))),
+                _ => None,
+            };
+
+            let dispatched = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(), XError> {
+                match m {
+                    Message::Key(k) => match ks.get_mut(&k) {
+                        Some(action) => action(h.clone()),
+                        None => Ok(()),
+                    },
+
+                    Message::Mouse(e) => match ms.get_mut(&(e.kind, e.state.clone())) {
+                        Some(action) => action(h.clone(), &e),
+                        None => Ok(()),
+                    },
+
+                    Message::Job(_, job, token) => job(h.clone().with_job_token(token)),
+
+                    Message::ShutDown => unreachable!("handled above"),
+                }
+            }));
+
+            if let Some((id, flag)) = &job_entry {
+                prune_completed_job(&jobs, id, flag);
+            }
+
+            match dispatched {
+                Ok(Ok(())) => {
+                    let _ = events_tx.send(WorkerEvent::Finished(label, started.elapsed()));
+                }
+                Ok(Err(e)) => {
+                    let _ = events_tx.send(WorkerEvent::Failed(e.clone()));
+                    error_handler(e);
+                }
+                Err(payload) => {
+                    let msg = panic_message(&payload);
+                    error!(id, %msg, "worker action panicked, isolating and continuing");
+                    let _ = events_tx.send(WorkerEvent::Failed(XError::WorkerPanic(msg.clone())));
+                    error_handler(XError::WorkerPanic(msg));
                 }
             }
         });
 
-        Self { id, handle }
+        Self {
+            id,
+            handle,
+            injector,
+        }
+    }
+}
+
+/// The state needed to spin up a new [`Worker`], kept around so that a
+/// worker whose thread has died can be replaced in place.
+#[derive(Clone)]
+struct SharedState {
+    h: WmHandle,
+    ks: KeyBindings,
+    ms: MouseBindings,
+    error_handler: ErrorHandler,
+    events_tx: Sender<WorkerEvent>,
+    jobs: Arc<Mutex<HashMap<JobId, JobHandle>>>,
+}
+
+/// Remove `id`'s entry from `jobs`, but only if it's still the entry for
+/// `flag` — a later job resubmitted under the same id (which would have
+/// replaced the entry, cancelling this one) must not be evicted here.
+fn prune_completed_job(
+    jobs: &Mutex<HashMap<JobId, JobHandle>>,
+    id: &JobId,
+    flag: &Arc<AtomicBool>,
+) {
+    let mut jobs = jobs.lock().unwrap();
+    if jobs
+        .get(id)
+        .is_some_and(|handle| Arc::ptr_eq(&handle.cancelled, flag))
+    {
+        jobs.remove(id);
     }
 }
 
+/// The `Stealer` half of every worker's local deque, indexed by worker id,
+/// so that a freshly (re)spawned worker can be handed its siblings' deques
+/// to steal from when idle.
+fn sibling_stealers(
+    stealers: &Mutex<Vec<Stealer<Message>>>,
+    exclude: usize,
+) -> Vec<Stealer<Message>> {
+    stealers
+        .lock()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != exclude)
+        .map(|(_, s)| s.clone())
+        .collect()
+}
+
 /// A worker pool for running jobs
-#[derive(Debug)]
 pub struct Pool {
-    workers: Vec<Worker>,
-    tx: Sender<Message>,
+    workers: Arc<Mutex<Vec<Worker>>>,
+    injectors: Vec<Arc<Injector<Message>>>,
+    next: AtomicUsize,
+    shutting_down: Arc<AtomicBool>,
+    reaper: Option<thread::JoinHandle<()>>,
+    jobs: Arc<Mutex<HashMap<JobId, JobHandle>>>,
+    events_tx: Sender<WorkerEvent>,
+    events_rx: Receiver<WorkerEvent>,
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("workers", &self.workers.lock().unwrap())
+            .finish()
+    }
 }
 
 impl Pool {
@@ -88,41 +399,340 @@ impl Pool {
     ) -> Self {
         assert!(size > 0, "attempt to create empty worker pool");
 
-        let (tx, rx) = unbounded();
-        let workers = (0..size)
-            .map(|id| {
+        let (events_tx, events_rx) = unbounded();
+        let jobs = Arc::new(Mutex::new(HashMap::new()));
+        let shared = SharedState {
+            h,
+            ks,
+            ms,
+            error_handler,
+            events_tx: events_tx.clone(),
+            jobs: Arc::clone(&jobs),
+        };
+        let shutting_down = Arc::new(AtomicBool::new(false));
+
+        let locals: Vec<Deque<Message>> = (0..size).map(|_| Deque::new_lifo()).collect();
+        let stealers = Arc::new(Mutex::new(
+            locals.iter().map(Deque::stealer).collect::<Vec<_>>(),
+        ));
+        let injectors: Vec<Arc<Injector<Message>>> =
+            (0..size).map(|_| Arc::new(Injector::new())).collect();
+
+        let workers: Vec<Worker> = locals
+            .into_iter()
+            .zip(injectors.iter().cloned())
+            .enumerate()
+            .map(|(id, (local, injector))| {
+                let siblings = sibling_stealers(&stealers, id);
                 Worker::new(
                     id,
-                    rx.clone(),
-                    h.clone(),
-                    ks.clone(),
-                    ms.clone(),
-                    error_handler,
+                    local,
+                    injector,
+                    siblings,
+                    &shared,
+                    Arc::clone(&shutting_down),
                 )
             })
             .collect();
+        let workers = Arc::new(Mutex::new(workers));
+
+        let reaper = {
+            let workers = Arc::clone(&workers);
+            let injectors = injectors.clone();
+            let stealers = Arc::clone(&stealers);
+            let shutting_down = Arc::clone(&shutting_down);
+            thread::spawn(move || loop {
+                thread::sleep(REAPER_POLL_INTERVAL);
+                if shutting_down.load(Ordering::Acquire) {
+                    return;
+                }
+
+                let mut workers = workers.lock().unwrap();
+                for i in 0..workers.len() {
+                    if workers[i].handle.is_finished() {
+                        let id = workers[i].id;
+                        error!(id, "worker thread exited unexpectedly, respawning");
+
+                        let local = Deque::new_lifo();
+                        stealers.lock().unwrap()[id] = local.stealer();
+                        let siblings = sibling_stealers(&stealers, id);
+
+                        workers[i] = Worker::new(
+                            id,
+                            local,
+                            Arc::clone(&injectors[id]),
+                            siblings,
+                            &shared,
+                            Arc::clone(&shutting_down),
+                        );
+                    }
+                }
+            })
+        };
+
+        Self {
+            workers,
+            injectors,
+            next: AtomicUsize::new(0),
+            shutting_down,
+            reaper: Some(reaper),
+            jobs,
+            events_tx,
+            events_rx,
+        }
+    }
 
-        Self { workers, tx }
+    /// A receiver for [`WorkerEvent`]s emitted as workers dispatch key,
+    /// mouse and job messages, so the main event loop (or a status-bar
+    /// integration) can observe progress, timing and failures without
+    /// polling.
+    pub fn events(&self) -> Receiver<WorkerEvent> {
+        self.events_rx.clone()
     }
 
-    /// Execute a function on the first available worker
-    pub fn exec<F>(&self, f: F)
+    /// Execute a function on the pool, tagged with `id`.
+    ///
+    /// `f` returns a `Result` just like a key/mouse binding does, so a job
+    /// that fails is reported as [`WorkerEvent::Failed`] rather than
+    /// [`WorkerEvent::Finished`] by the same dispatch loop that handles
+    /// key/mouse actions.
+    ///
+    /// The job is pushed onto one of the pool's injectors in round-robin
+    /// order, excluding the designated worker that ordered key/mouse events
+    /// are pinned to, so independent jobs spread across the remaining
+    /// workers rather than piling up behind a single busy one or queueing up
+    /// among the pinned events on the designated worker's own injector
+    /// (a single-worker pool has only that injector, so jobs go there).
+    /// Submitting another job under the same `id` before this one
+    /// completes cancels it: its [`JobToken`] is marked cancelled so a
+    /// cooperating job can notice and abort early (e.g. a superseded
+    /// "resize preview").
+    ///
+    /// The returned [`JobHandle`] does *not* cancel the job if it is simply
+    /// dropped (see [`JobHandle`]'s docs) — call [`JobHandle::cancel`]
+    /// explicitly if that's what you want.
+    pub fn exec<F>(&self, id: impl Into<JobId>, f: F) -> JobHandle
     where
-        F: FnOnce() + Send + 'static,
+        F: FnOnce(WmHandle) -> Result<(), XError> + Send + 'static,
     {
-        // TODO: should be returning an error from this method
-        self.tx.send(Message::Job(Box::new(f))).unwrap()
+        let id = id.into();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let token = JobToken(Arc::clone(&cancelled));
+        let in_flight = JobHandle {
+            id: id.clone(),
+            cancelled: Arc::clone(&cancelled),
+            cancel_on_drop: true,
+        };
+        let caller_handle = JobHandle {
+            id,
+            cancelled,
+            cancel_on_drop: false,
+        };
+
+        // Insert the bookkeeping entry *before* pushing the message: a
+        // worker must never be able to dequeue and finish this job before
+        // its entry exists in `jobs`, or `prune_completed_job` finds nothing
+        // to prune and the entry `insert` goes on to add is never cleaned
+        // up. Replacing an existing entry here drops (and so cancels) the
+        // job it superseded.
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert(caller_handle.id.clone(), in_flight);
+
+        let target = self.round_robin_target();
+        self.injectors[target].push(Message::Job(caller_handle.id.clone(), Box::new(f), token));
+
+        caller_handle
+    }
+
+    /// Pick the injector a job submitted via [`Pool::exec`] should be pushed
+    /// onto, round-robining over every worker *except* the designated one:
+    /// that injector is reserved for ordered key/mouse events, which its
+    /// worker takes one at a time (see [`find_task`]), so a job landing on
+    /// it would hold up every event queued behind it. A pool with a single
+    /// worker has no other injector, so there the designated one is used.
+    fn round_robin_target(&self) -> usize {
+        let size = self.injectors.len();
+        if size == 1 {
+            return DESIGNATED_WORKER;
+        }
+        let offset = self.next.fetch_add(1, Ordering::Relaxed) % (size - 1);
+        (DESIGNATED_WORKER + 1 + offset) % size
+    }
+
+    /// Dispatch a key event. Pinned to the pool's designated worker so that
+    /// ordered X events are never reordered relative to one another by
+    /// work-stealing.
+    pub(crate) fn dispatch_key(&self, k: KeyCode) {
+        self.injectors[DESIGNATED_WORKER].push(Message::Key(k));
+    }
+
+    /// Dispatch a mouse event. Pinned to the pool's designated worker so
+    /// that ordered X events are never reordered relative to one another by
+    /// work-stealing.
+    pub(crate) fn dispatch_mouse(&self, e: MouseEvent) {
+        self.injectors[DESIGNATED_WORKER].push(Message::Mouse(e));
+    }
+
+    /// Spawn a child process on the pool without blocking a worker for the
+    /// lifetime of the child (the conductor runner pattern).
+    ///
+    /// `stdout`/`stderr` are piped and streamed line-by-line as
+    /// [`WorkerEvent::Log`]s as they arrive. If `timeout` elapses before the
+    /// child exits it is killed and a [`WorkerEvent::TimedOut`] is emitted
+    /// in place of the usual [`WorkerEvent::ProcessExited`]. If the child
+    /// fails to spawn in the first place, a single [`WorkerEvent::Failed`]
+    /// is emitted in place of [`WorkerEvent::Finished`] -- never both. This
+    /// keeps launcher bindings (terminals, launchers, screenshot tools) from
+    /// blocking a worker, and lets their output be wired into
+    /// notifications or the status bar via [`Pool::events`].
+    ///
+    /// It's safe to call this and discard the returned [`JobHandle`], as is
+    /// typical for a fire-and-forget launcher binding — dropping it does not
+    /// cancel the child (see [`JobHandle`]'s docs).
+    pub fn spawn_process(
+        &self,
+        id: impl Into<JobId>,
+        cmd: impl Into<OsString>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+        env: impl IntoIterator<Item = (String, String)>,
+        cwd: Option<impl Into<PathBuf>>,
+        timeout: Option<Duration>,
+    ) -> JobHandle {
+        let id = id.into();
+        let cmd = cmd.into();
+        let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        let env: Vec<(String, String)> = env.into_iter().collect();
+        let cwd = cwd.map(Into::into);
+        let events_tx = self.events_tx.clone();
+        let label = id.clone();
+
+        self.exec(id, move |h| {
+            let started = Instant::now();
+            let token = h.job_token();
+
+            let mut command = Command::new(cmd);
+            command
+                .args(args)
+                .envs(env)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            if let Some(dir) = cwd {
+                command.current_dir(dir);
+            }
+
+            let mut child = command.spawn().map_err(|e| XError::Spawn(e.to_string()))?;
+
+            let stdout = child.stdout.take().expect("stdout was piped");
+            let stderr = child.stderr.take().expect("stderr was piped");
+
+            let stream = |tx: Sender<WorkerEvent>, label: JobId, out: Box<dyn Read + Send>| {
+                thread::spawn(move || stream_lines(out, &tx, &label))
+            };
+            let out_thread = stream(events_tx.clone(), label.clone(), Box::new(stdout));
+            let err_thread = stream(events_tx.clone(), label.clone(), Box::new(stderr));
+
+            // Waiting on the child (unbounded when `timeout` is `None`) happens on
+            // its own thread rather than inline here, so this job returns the
+            // worker to the pool immediately instead of tying it up for however
+            // long the launched program keeps running.
+            thread::spawn(move || {
+                let status = wait_for_child(&mut child, timeout, &token, &events_tx, &label);
+
+                let _ = out_thread.join();
+                let _ = err_thread.join();
+
+                if let Some(status) = status {
+                    let _ = events_tx.send(WorkerEvent::ProcessExited(
+                        label,
+                        status,
+                        started.elapsed(),
+                    ));
+                }
+            });
+
+            Ok(())
+        })
+    }
+}
+
+/// Streams `out` line-by-line as [`WorkerEvent::Log`]s, tolerating non-UTF-8
+/// output instead of silently stopping on the first invalid line.
+fn stream_lines(out: impl Read, tx: &Sender<WorkerEvent>, label: &JobId) {
+    let mut reader = BufReader::new(out);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {
+                let text = String::from_utf8_lossy(&line);
+                let _ = tx.send(WorkerEvent::Log(format!("[{label}] {}", text.trim_end())));
+            }
+        }
+    }
+}
+
+/// Waits for `child` to exit, killing it if `timeout` elapses first or if
+/// `token` is cancelled in the meantime (e.g. via [`JobHandle::cancel`] on
+/// the handle [`Pool::spawn_process`] returned). The pool's own entry for
+/// the job is pruned once the launching job returns, so a job submitted
+/// later under the same id does not cancel the child. In every case the
+/// child is reaped before returning so
+/// its stdout/stderr pipes close and the reader threads streaming them can
+/// join.
+fn wait_for_child(
+    child: &mut std::process::Child,
+    timeout: Option<Duration>,
+    token: &JobToken,
+    events_tx: &Sender<WorkerEvent>,
+    label: &JobId,
+) -> Option<ExitStatus> {
+    let deadline = timeout.map(|limit| Instant::now() + limit);
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) if deadline.is_some_and(|d| Instant::now() >= d) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                let _ = events_tx.send(WorkerEvent::TimedOut(label.clone()));
+                return None;
+            }
+            Ok(None) if token.is_cancelled() => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+            Ok(None) => thread::sleep(IDLE_BACKOFF),
+            Err(_) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
     }
 }
 
 impl Drop for Pool {
     fn drop(&mut self) {
+        self.shutting_down.store(true, Ordering::Release);
+        if let Some(reaper) = self.reaper.take() {
+            reaper.join().unwrap(); // TODO: remove unwrap
+        }
+
+        trace!("cancelling outstanding jobs");
+        for (_, handle) in self.jobs.lock().unwrap().drain() {
+            handle.cancel();
+        }
+
         trace!("Sending shutdown signal to all workers");
-        for _ in &self.workers {
-            self.tx.send(Message::ShutDown).unwrap(); // TODO: remove unwrap
+        for injector in &self.injectors {
+            injector.push(Message::ShutDown);
         }
 
-        for w in self.workers.drain(0..) {
+        let mut workers = self.workers.lock().unwrap();
+        for w in workers.drain(0..) {
             trace!(w.id, "shutting down worker");
             w.handle.join().unwrap(); // TODO: remove unwrap
         }
@@ -132,16 +742,26 @@ impl Drop for Pool {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::v3::{bindings::KeyBindings, bindings::MouseBindings, handle::WmHandle};
+
+    fn err_handler(_: XError) {}
 
     #[test]
     fn work_gets_done() {
         let (tx, rx) = unbounded();
-        let p = Pool::new(2);
+        let p = Pool::new(
+            2,
+            WmHandle::default(),
+            KeyBindings::default(),
+            MouseBindings::default(),
+            err_handler,
+        );
 
         for n in 0..10 {
             let ch = tx.clone();
-            p.exec(move || {
+            p.exec(format!("job-{n}"), move |_| {
                 ch.send(n).unwrap();
+                Ok(())
             });
         }
 
@@ -153,4 +773,212 @@ mod test {
         nums.sort();
         assert_eq!(nums, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn worker_panic_is_isolated() {
+        let (tx, rx) = unbounded();
+        let p = Pool::new(
+            2,
+            WmHandle::default(),
+            KeyBindings::default(),
+            MouseBindings::default(),
+            err_handler,
+        );
+
+        p.exec("boom", |_| panic!("boom"));
+
+        let ch = tx.clone();
+        p.exec("after-panic", move |_| {
+            ch.send(()).unwrap();
+            Ok(())
+        });
+
+        rx.recv_timeout(Duration::from_secs(1))
+            .expect("pool kept running a later job after an earlier one panicked");
+    }
+
+    #[test]
+    fn superseding_job_cancels_the_token_of_the_one_it_replaces() {
+        let (tx, rx) = unbounded();
+        let p = Pool::new(
+            2,
+            WmHandle::default(),
+            KeyBindings::default(),
+            MouseBindings::default(),
+            err_handler,
+        );
+
+        let ch = tx.clone();
+        p.exec("resize-preview", move |h| {
+            let token = h.job_token();
+            while !token.is_cancelled() {
+                thread::sleep(IDLE_BACKOFF);
+            }
+            ch.send(()).unwrap();
+            Ok(())
+        });
+
+        // Give the first job a chance to start and observe the token before
+        // it's superseded.
+        thread::sleep(Duration::from_millis(20));
+        p.exec("resize-preview", |_| Ok(()));
+
+        rx.recv_timeout(Duration::from_secs(1))
+            .expect("superseded job should have observed its token as cancelled");
+    }
+
+    #[test]
+    fn exec_round_robin_never_targets_the_designated_worker() {
+        let p = Arc::new(Pool::new(
+            4,
+            WmHandle::default(),
+            KeyBindings::default(),
+            MouseBindings::default(),
+            err_handler,
+        ));
+
+        // The designated worker's injector is reserved for ordered key/mouse
+        // events; a job landing there would hold up the events queued behind
+        // it. Hammer the round-robin picker from several
+        // threads at once and check it's never chosen.
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let p = Arc::clone(&p);
+                thread::spawn(move || (0..50).map(|_| p.round_robin_target()).collect::<Vec<_>>())
+            })
+            .collect();
+
+        for h in handles {
+            for target in h.join().unwrap() {
+                assert_ne!(target, DESIGNATED_WORKER);
+            }
+        }
+    }
+
+    #[test]
+    fn designated_worker_find_task_preserves_fifo_order() {
+        // Simulate a burst of ordered events queued up while the designated
+        // worker is busy: several messages land on its injector before it
+        // next calls `find_task`. The `ordered` path must hand them back in
+        // the order they were pushed, not reversed by a batch steal into the
+        // LIFO local deque.
+        let local: Deque<Message> = Deque::new_lifo();
+        let injector: Injector<Message> = Injector::new();
+
+        for n in 0..5 {
+            let token = JobToken(Arc::new(AtomicBool::new(false)));
+            injector.push(Message::Job(n.to_string(), Box::new(|_| Ok(())), token));
+        }
+
+        let mut order = Vec::new();
+        for _ in 0..5 {
+            let m = find_task(&local, &injector, &[], true).expect("message should be available");
+            order.push(m.label());
+        }
+
+        assert_eq!(order, vec!["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn events_report_started_then_finished_for_a_plain_exec() {
+        let p = Pool::new(
+            2,
+            WmHandle::default(),
+            KeyBindings::default(),
+            MouseBindings::default(),
+            err_handler,
+        );
+        let events = p.events();
+
+        p.exec("job", |_| Ok(()));
+
+        match events.recv_timeout(Duration::from_secs(1)) {
+            Ok(WorkerEvent::Started(id)) => assert_eq!(id, "job"),
+            other => panic!("expected Started(\"job\"), got {other:?}"),
+        }
+
+        match events.recv_timeout(Duration::from_secs(1)) {
+            Ok(WorkerEvent::Finished(id, _)) => assert_eq!(id, "job"),
+            other => panic!("expected Finished(\"job\", _), got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn spawn_process_streams_output_and_reports_exit_status() {
+        let p = Pool::new(
+            2,
+            WmHandle::default(),
+            KeyBindings::default(),
+            MouseBindings::default(),
+            err_handler,
+        );
+        let events = p.events();
+
+        p.spawn_process(
+            "echo-test",
+            "echo",
+            ["hello"],
+            Vec::<(String, String)>::new(),
+            None::<PathBuf>,
+            None,
+        );
+
+        let mut saw_log = false;
+        let mut saw_exit = false;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline && !(saw_log && saw_exit) {
+            let Ok(event) = events.recv_timeout(Duration::from_millis(200)) else {
+                continue;
+            };
+            match event {
+                WorkerEvent::Log(line) if line.contains("hello") => saw_log = true,
+                WorkerEvent::ProcessExited(id, status, _) if id == "echo-test" => {
+                    assert!(status.success());
+                    saw_exit = true;
+                }
+                _ => {}
+            }
+        }
+
+        assert!(saw_log, "expected a Log event with the child's stdout");
+        assert!(saw_exit, "expected a ProcessExited event for the child");
+    }
+
+    #[test]
+    fn spawn_process_kills_and_reports_timeout_for_long_running_children() {
+        let p = Pool::new(
+            2,
+            WmHandle::default(),
+            KeyBindings::default(),
+            MouseBindings::default(),
+            err_handler,
+        );
+        let events = p.events();
+
+        p.spawn_process(
+            "sleep-test",
+            "sleep",
+            ["5"],
+            Vec::<(String, String)>::new(),
+            None::<PathBuf>,
+            Some(Duration::from_millis(100)),
+        );
+
+        let mut timed_out = false;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline && !timed_out {
+            let Ok(event) = events.recv_timeout(Duration::from_millis(200)) else {
+                continue;
+            };
+            if let WorkerEvent::TimedOut(id) = event {
+                assert_eq!(id, "sleep-test");
+                timed_out = true;
+            }
+        }
+
+        assert!(
+            timed_out,
+            "expected the long-running child to be killed and reported via TimedOut"
+        );
+    }
+}